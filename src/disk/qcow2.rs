@@ -0,0 +1,498 @@
+//! Minimal qcow2 disk image support.
+//!
+//! Understands just enough of the qcow2 v2/v3 format - the header, and the
+//! two-level L1/L2 cluster map - to translate a logical (guest) byte offset
+//! to a host file offset. Unallocated clusters read back as zero; writes
+//! allocate a fresh cluster by appending it to the end of the file and
+//! back-patching the owning L2 entry (and its refcount), so a GPT header or
+//! table can be written into a VM image without converting it to raw first.
+//!
+//! This is deliberately not a full qcow2 implementation: there is no
+//! snapshot, backing-file, compression, or encryption support, and refcount
+//! bookkeeping assumes the common 16-bit (`refcount_order == 4`) case.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path,
+};
+
+use super::{DiskDevice, LogicalBlockSize};
+use crate::GptError;
+
+const QCOW2_MAGIC: [u8; 4] = *b"QFI\xfb";
+const L1_L2_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const DEFAULT_REFCOUNT_ORDER: u32 = 4;
+
+// Byte offsets of the header fields we rewrite when the L1 table is grown
+// and relocated; see the field order read out in `Qcow2Disk::open`.
+const L1_SIZE_FIELD_OFFSET: u64 = 36;
+const L1_TABLE_OFFSET_FIELD_OFFSET: u64 = 40;
+
+struct Qcow2Header {
+    cluster_bits: u32,
+    size: u64,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_order: u32,
+}
+
+impl Qcow2Header {
+    fn cluster_size(&self) -> u64 {
+        1u64 << self.cluster_bits
+    }
+}
+
+/// A qcow2 disk image, usable anywhere a [`DiskDevice`] is expected.
+pub struct Qcow2Disk {
+    file: fs::File,
+    header: Qcow2Header,
+    l1_table: Vec<u64>,
+    position: u64,
+}
+
+impl Qcow2Disk {
+    /// Open an existing qcow2 image, parsing its header and L1 table.
+    pub fn open(path: impl AsRef<path::Path>) -> Result<Self, GptError> {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if magic != QCOW2_MAGIC {
+            return Err(GptError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a qcow2 image: bad magic",
+            )));
+        }
+
+        let version = read_u32(&mut file)?;
+        let _backing_file_offset = read_u64(&mut file)?;
+        let _backing_file_size = read_u32(&mut file)?;
+        let cluster_bits = read_u32(&mut file)?;
+        let size = read_u64(&mut file)?;
+        let _crypt_method = read_u32(&mut file)?;
+        let l1_size = read_u32(&mut file)?;
+        let l1_table_offset = read_u64(&mut file)?;
+        let refcount_table_offset = read_u64(&mut file)?;
+        let _refcount_table_clusters = read_u32(&mut file)?;
+        let _nb_snapshots = read_u32(&mut file)?;
+        let _snapshots_offset = read_u64(&mut file)?;
+
+        // v3 header fields; v2 images always use 16-bit refcounts.
+        let refcount_order = if version >= 3 {
+            let _incompatible_features = read_u64(&mut file)?;
+            let _compatible_features = read_u64(&mut file)?;
+            let _autoclear_features = read_u64(&mut file)?;
+            let refcount_order = read_u32(&mut file)?;
+            let _header_length = read_u32(&mut file)?;
+            refcount_order
+        } else {
+            DEFAULT_REFCOUNT_ORDER
+        };
+
+        let header = Qcow2Header {
+            cluster_bits,
+            size,
+            l1_table_offset,
+            refcount_table_offset,
+            refcount_order,
+        };
+
+        let mut l1_table = vec![0u64; l1_size as usize];
+        file.seek(SeekFrom::Start(l1_table_offset))?;
+        for entry in l1_table.iter_mut() {
+            *entry = read_u64(&mut file)?;
+        }
+
+        Ok(Qcow2Disk { file, header, l1_table, position: 0 })
+    }
+
+    fn l2_entries_per_cluster(&self) -> u64 {
+        self.header.cluster_size() / 8
+    }
+
+    /// Translate a logical offset into a host file offset, allocating new
+    /// L2/data clusters along the way when `allocate` is set. Returns `None`
+    /// for an unallocated cluster when `allocate` is false, which the caller
+    /// treats as a run of zero bytes.
+    fn translate(&mut self, logical_offset: u64, allocate: bool) -> io::Result<Option<u64>> {
+        let cluster_size = self.header.cluster_size();
+        let l2_entries = self.l2_entries_per_cluster();
+
+        let cluster_index = logical_offset >> self.header.cluster_bits;
+        let l1_index = (cluster_index / l2_entries) as usize;
+        let l2_index = cluster_index % l2_entries;
+        let in_cluster = logical_offset & (cluster_size - 1);
+
+        if l1_index >= self.l1_table.len() {
+            if !allocate {
+                return Ok(None);
+            }
+            self.grow_l1_table(l1_index + 1)?;
+        }
+
+        let mut l2_table_offset = self.l1_table[l1_index] & L1_L2_OFFSET_MASK;
+        if l2_table_offset == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            l2_table_offset = self.allocate_cluster()?;
+            self.zero_cluster(l2_table_offset)?;
+            self.bump_refcount(l2_table_offset)?;
+            self.l1_table[l1_index] = l2_table_offset;
+            self.write_l1_entry(l1_index)?;
+        }
+
+        let l2_entry_offset = l2_table_offset + l2_index * 8;
+        self.file.seek(SeekFrom::Start(l2_entry_offset))?;
+        let mut cluster_offset = read_u64(&mut self.file)? & L1_L2_OFFSET_MASK;
+
+        if cluster_offset == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            cluster_offset = self.allocate_cluster()?;
+            self.zero_cluster(cluster_offset)?;
+            self.file.seek(SeekFrom::Start(l2_entry_offset))?;
+            write_u64(&mut self.file, cluster_offset)?;
+            self.bump_refcount(cluster_offset)?;
+        }
+
+        Ok(Some(cluster_offset + in_cluster))
+    }
+
+    /// Append a fresh, zero-refcount cluster to the end of the file and
+    /// return its host offset.
+    fn allocate_cluster(&mut self) -> io::Result<u64> {
+        self.allocate_region(self.header.cluster_size())
+    }
+
+    /// Append `len` bytes of fresh storage to the end of the file,
+    /// cluster-aligned, and return its host offset. Like `allocate_cluster`
+    /// (which is built on it), this doesn't mark anything as referenced - the
+    /// caller is responsible for calling `bump_refcount` per cluster once it
+    /// knows what's actually being stored there.
+    fn allocate_region(&mut self, len: u64) -> io::Result<u64> {
+        let cluster_size = self.header.cluster_size();
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        // Round up to a cluster boundary; real qcow2 files are always
+        // cluster-aligned, but be defensive about a truncated image.
+        let aligned = (offset + cluster_size - 1) & !(cluster_size - 1);
+        let aligned_len = (len + cluster_size - 1) & !(cluster_size - 1);
+        self.file.set_len(aligned + aligned_len)?;
+        Ok(aligned)
+    }
+
+    /// Grow the in-memory and on-disk L1 table to cover at least `min_len`
+    /// entries, relocating it to freshly allocated (and refcounted) clusters
+    /// and rewriting the header's `l1_size`/`l1_table_offset` fields to
+    /// point at the new location.
+    fn grow_l1_table(&mut self, min_len: usize) -> io::Result<()> {
+        if min_len <= self.l1_table.len() {
+            return Ok(());
+        }
+
+        self.l1_table.resize(min_len, 0);
+
+        let l1_bytes = (self.l1_table.len() as u64) * 8;
+        let new_l1_offset = self.allocate_region(l1_bytes)?;
+
+        let cluster_size = self.header.cluster_size();
+        let mut cluster_offset = new_l1_offset;
+        while cluster_offset < new_l1_offset + l1_bytes {
+            self.bump_refcount(cluster_offset)?;
+            cluster_offset += cluster_size;
+        }
+
+        self.file.seek(SeekFrom::Start(new_l1_offset))?;
+        for &entry in &self.l1_table {
+            write_u64(&mut self.file, entry)?;
+        }
+
+        self.header.l1_table_offset = new_l1_offset;
+
+        self.file.seek(SeekFrom::Start(L1_SIZE_FIELD_OFFSET))?;
+        self.file.write_all(&(self.l1_table.len() as u32).to_be_bytes())?;
+
+        self.file.seek(SeekFrom::Start(L1_TABLE_OFFSET_FIELD_OFFSET))?;
+        write_u64(&mut self.file, new_l1_offset)
+    }
+
+    fn zero_cluster(&mut self, host_offset: u64) -> io::Result<()> {
+        let zeros = vec![0u8; self.header.cluster_size() as usize];
+        self.file.seek(SeekFrom::Start(host_offset))?;
+        self.file.write_all(&zeros)
+    }
+
+    fn write_l1_entry(&mut self, l1_index: usize) -> io::Result<()> {
+        let entry_offset = self.header.l1_table_offset + (l1_index as u64) * 8;
+        self.file.seek(SeekFrom::Start(entry_offset))?;
+        write_u64(&mut self.file, self.l1_table[l1_index])
+    }
+
+    /// Mark a newly allocated data/L2 cluster as referenced, assuming the
+    /// common 16-bit refcount table layout.
+    fn bump_refcount(&mut self, cluster_host_offset: u64) -> io::Result<()> {
+        if self.header.refcount_order != DEFAULT_REFCOUNT_ORDER {
+            // Unsupported refcount width: leave bookkeeping untouched rather
+            // than corrupt it with a wrong-sized write.
+            return Ok(());
+        }
+
+        let cluster_bits = self.header.cluster_bits;
+        let entries_per_cluster = self.header.cluster_size() / 2;
+        let refcount_block_index = cluster_host_offset >> cluster_bits;
+        let rb_table_index = refcount_block_index / entries_per_cluster;
+        let rb_entry_index = refcount_block_index % entries_per_cluster;
+
+        let rb_table_entry_offset = self.header.refcount_table_offset + rb_table_index * 8;
+        self.file.seek(SeekFrom::Start(rb_table_entry_offset))?;
+        let refcount_block_offset = read_u64(&mut self.file)? & L1_L2_OFFSET_MASK;
+        if refcount_block_offset == 0 {
+            // The refcount block itself isn't allocated; out of scope for
+            // this minimal implementation.
+            return Ok(());
+        }
+
+        let entry_offset = refcount_block_offset + rb_entry_index * 2;
+        self.file.seek(SeekFrom::Start(entry_offset))?;
+        let mut count = [0u8; 2];
+        self.file.read_exact(&mut count)?;
+        let count = u16::from_be_bytes(count).saturating_add(1);
+        self.file.seek(SeekFrom::Start(entry_offset))?;
+        self.file.write_all(&count.to_be_bytes())
+    }
+}
+
+impl Read for Qcow2Disk {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let cluster_size = self.header.cluster_size();
+        let remaining = self.header.size.saturating_sub(self.position);
+        let to_read = (buf.len() as u64).min(remaining) as usize;
+        if to_read == 0 {
+            return Ok(0);
+        }
+
+        let mut done = 0;
+        while done < to_read {
+            let logical_offset = self.position;
+            let in_cluster = (logical_offset & (cluster_size - 1)) as usize;
+            let chunk = ((cluster_size as usize) - in_cluster).min(to_read - done);
+
+            match self.translate(logical_offset, false)? {
+                Some(host_offset) => {
+                    self.file.seek(SeekFrom::Start(host_offset))?;
+                    self.file.read_exact(&mut buf[done..done + chunk])?;
+                }
+                None => {
+                    buf[done..done + chunk].fill(0);
+                }
+            }
+
+            self.position += chunk as u64;
+            done += chunk;
+        }
+
+        Ok(done)
+    }
+}
+
+impl Write for Qcow2Disk {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let cluster_size = self.header.cluster_size();
+        let mut done = 0;
+
+        while done < buf.len() {
+            let logical_offset = self.position;
+            let in_cluster = (logical_offset & (cluster_size - 1)) as usize;
+            let chunk = ((cluster_size as usize) - in_cluster).min(buf.len() - done);
+
+            let host_offset = self.translate(logical_offset, true)?.ok_or_else(|| {
+                io::Error::other(
+                    "qcow2: failed to allocate a cluster for a logical offset that should have been allocated",
+                )
+            })?;
+            self.file.seek(SeekFrom::Start(host_offset))?;
+            self.file.write_all(&buf[done..done + chunk])?;
+
+            self.position += chunk as u64;
+            done += chunk;
+        }
+
+        self.header.size = self.header.size.max(self.position);
+        Ok(done)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for Qcow2Disk {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => (self.header.size as i64 + offset) as u64,
+            SeekFrom::Current(offset) => (self.position as i64 + offset) as u64,
+        };
+        Ok(self.position)
+    }
+}
+
+impl DiskDevice for Qcow2Disk {
+    fn logical_block_size(&self) -> LogicalBlockSize {
+        super::DEFAULT_SECTOR_SIZE
+    }
+
+    fn device_len(&self) -> Result<u64, GptError> {
+        Ok(self.header.size)
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn write_u64(w: &mut impl Write, value: u64) -> io::Result<()> {
+    w.write_all(&value.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Hand-build a minimal v3 qcow2 image: a 512-byte header cluster, a
+    /// refcount table cluster, a refcount block cluster, and a one-entry L1
+    /// table cluster, all with 512-byte clusters (`cluster_bits = 9`).
+    fn build_minimal_qcow2(path: &path::Path) {
+        const CLUSTER_SIZE: usize = 512;
+        let mut image = vec![0u8; CLUSTER_SIZE * 4];
+
+        image[0..4].copy_from_slice(&QCOW2_MAGIC);
+        image[4..8].copy_from_slice(&3u32.to_be_bytes()); // version
+        image[20..24].copy_from_slice(&9u32.to_be_bytes()); // cluster_bits
+        image[24..32].copy_from_slice(&(1u64 << 20).to_be_bytes()); // virtual disk size: 1MiB
+        image[36..40].copy_from_slice(&1u32.to_be_bytes()); // l1_size
+        image[40..48].copy_from_slice(&(CLUSTER_SIZE as u64 * 3).to_be_bytes()); // l1_table_offset
+        image[48..56].copy_from_slice(&(CLUSTER_SIZE as u64).to_be_bytes()); // refcount_table_offset
+        image[56..60].copy_from_slice(&1u32.to_be_bytes()); // refcount_table_clusters
+        image[96..100].copy_from_slice(&DEFAULT_REFCOUNT_ORDER.to_be_bytes());
+        image[100..104].copy_from_slice(&104u32.to_be_bytes()); // header_length
+
+        // Refcount table cluster (at CLUSTER_SIZE): one entry pointing at the
+        // refcount block cluster (at CLUSTER_SIZE * 2).
+        let rc_table_entry = CLUSTER_SIZE..CLUSTER_SIZE + 8;
+        image[rc_table_entry].copy_from_slice(&(CLUSTER_SIZE as u64 * 2).to_be_bytes());
+
+        // Refcount block cluster (at CLUSTER_SIZE * 2) starts out all zero -
+        // no clusters referenced yet. L1 table cluster (at CLUSTER_SIZE * 3)
+        // also starts out all zero - its one entry is unallocated.
+
+        fs::write(path, &image).unwrap();
+    }
+
+    fn temp_image_path(name: &str) -> path::PathBuf {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("gpt-toolbox-qcow2-test-{}-{}-{}.qcow2", std::process::id(), n, name))
+    }
+
+    #[test]
+    fn round_trips_a_single_cluster_write() {
+        let path = temp_image_path("single-cluster");
+        build_minimal_qcow2(&path);
+
+        {
+            let mut disk = Qcow2Disk::open(&path).unwrap();
+            disk.write_all(b"primary-gpt-header").unwrap();
+        }
+
+        let mut disk = Qcow2Disk::open(&path).unwrap();
+        let mut readback = [0u8; 18];
+        disk.read_exact(&mut readback).unwrap();
+        assert_eq!(&readback, b"primary-gpt-header");
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn round_trips_a_write_that_grows_the_l1_table() {
+        let path = temp_image_path("l1-growth");
+        build_minimal_qcow2(&path);
+
+        // One L1 entry covers `cluster_size / 8` L2 entries, each mapping one
+        // 512-byte cluster - 64 clusters, i.e. 32768 bytes. Writing past that
+        // forces `translate` to grow the L1 table.
+        let offset_past_first_l1_entry = 32768u64;
+
+        {
+            let mut disk = Qcow2Disk::open(&path).unwrap();
+            disk.write_all(b"primary-gpt-header").unwrap();
+            disk.seek(SeekFrom::Start(offset_past_first_l1_entry)).unwrap();
+            disk.write_all(b"backup-gpt-header!").unwrap();
+        }
+
+        // Reopen so the read-back only sees what was actually persisted to
+        // disk, including the relocated/grown L1 table.
+        let mut disk = Qcow2Disk::open(&path).unwrap();
+        assert!(disk.l1_table.len() >= 2);
+
+        let mut readback = [0u8; 18];
+        disk.read_exact(&mut readback).unwrap();
+        assert_eq!(&readback, b"primary-gpt-header");
+
+        disk.seek(SeekFrom::Start(offset_past_first_l1_entry)).unwrap();
+        let mut readback = [0u8; 18];
+        disk.read_exact(&mut readback).unwrap();
+        assert_eq!(&readback, b"backup-gpt-header!");
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn unallocated_clusters_read_back_as_zero() {
+        let path = temp_image_path("sparse-read");
+        build_minimal_qcow2(&path);
+
+        let mut disk = Qcow2Disk::open(&path).unwrap();
+        let mut readback = [0xffu8; 512];
+        disk.read_exact(&mut readback).unwrap();
+        assert_eq!(&readback[..], &[0u8; 512][..]);
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn open_device_accepts_a_gpt_formatted_qcow2_disk() {
+        use crate::GptConfig;
+        use crate::disk::gpt_fixture;
+
+        // Matches the 1MiB virtual disk size `build_minimal_qcow2` declares,
+        // so the backup header lands on the last LBA of the reopened image.
+        // At 32KiB per L1 entry, the write below grows the fixture's
+        // one-entry L1 table to 32 entries.
+        let image = gpt_fixture::build(2048);
+
+        let path = temp_image_path("gpt-formatted");
+        build_minimal_qcow2(&path);
+
+        {
+            let mut disk = Qcow2Disk::open(&path).unwrap();
+            disk.write_all(&image).unwrap();
+        }
+
+        let disk = Qcow2Disk::open(&path).unwrap();
+        GptConfig::new().open_device(disk).unwrap();
+
+        fs::remove_file(&path).ok();
+    }
+}